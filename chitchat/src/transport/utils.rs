@@ -1,6 +1,9 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use rand::distributions::Bernoulli;
@@ -8,11 +11,29 @@ use rand::prelude::{Distribution, SmallRng};
 use rand::{thread_rng, SeedableRng};
 use tokio::sync::RwLock;
 
+use crate::serialize::Serializable;
 use crate::transport::{Socket, Transport, TransportError};
 use crate::ChitchatMessage;
 
+/// Derives a per-socket seed from a run-level seed and that socket's listen
+/// address, so distinct sockets in the same simulation diverge while the whole
+/// run stays reproducible from a single recorded seed.
+fn derive_seed(seed: u64, listen_addr: SocketAddr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    listen_addr.hash(&mut hasher);
+    seed ^ hasher.finish()
+}
+
+fn rng_for(seed: Option<u64>, listen_addr: SocketAddr) -> SmallRng {
+    match seed {
+        Some(seed) => SmallRng::seed_from_u64(derive_seed(seed, listen_addr)),
+        None => SmallRng::from_rng(thread_rng()).unwrap(),
+    }
+}
+
 struct TransportWithDelay<D: Distribution<f32> + Send + Sync + 'static> {
     delay_secs: D,
+    seed: Option<u64>,
     transport: Box<dyn Transport>,
 }
 
@@ -21,7 +42,7 @@ pub trait DelayMillisDist: Distribution<f32> + Send + Sync + Clone + 'static {}
 #[async_trait]
 impl<D: DelayMillisDist> Transport for TransportWithDelay<D> {
     async fn open(&self, listen_addr: SocketAddr) -> Result<Box<dyn Socket>, TransportError> {
-        let rng = SmallRng::from_rng(thread_rng()).unwrap();
+        let rng = rng_for(self.seed, listen_addr);
         let socket = self.transport.open(listen_addr).await?;
         Ok(Box::new(SocketWithDelay {
             delay_secs: self.delay_secs.clone(),
@@ -62,12 +83,26 @@ impl<D: DelayMillisDist> Socket for SocketWithDelay<D> {
 pub trait TransportExt {
     fn drop_message(self, drop_probability: f64) -> Box<dyn Transport>;
     fn delay<D: DelayMillisDist>(self, delay_proba: D) -> Box<dyn Transport>;
+    fn throttle_bandwidth(self, kbps: u64) -> Box<dyn Transport>;
+    fn drop_message_bursty(
+        self,
+        p_good_to_bad: f64,
+        p_bad_to_good: f64,
+        loss_good: f64,
+        loss_bad: f64,
+    ) -> Box<dyn Transport>;
+    fn partitioned(self) -> (Box<dyn Transport>, PartitionHandle);
+    fn drop_message_seeded(self, drop_probability: f64, seed: u64) -> Box<dyn Transport>;
+    fn delay_seeded<D: DelayMillisDist>(self, dist: D, seed: u64) -> Box<dyn Transport>;
+    fn duplicate<D: DelayMillisDist>(self, dup_probability: f64, extra_delay: D)
+        -> Box<dyn Transport>;
 }
 
 impl<T: Transport> TransportExt for T {
     fn drop_message(self, drop_probability: f64) -> Box<dyn Transport> {
         Box::new(TransportWithMessageDrop {
             drop_probability: Bernoulli::new(drop_probability).unwrap(),
+            seed: None,
             transport: Box::new(self),
         })
     }
@@ -75,6 +110,70 @@ impl<T: Transport> TransportExt for T {
     fn delay<D: DelayMillisDist>(self, delay_secs: D) -> Box<dyn Transport> {
         Box::new(TransportWithDelay {
             delay_secs,
+            seed: None,
+            transport: Box::new(self),
+        })
+    }
+
+    fn throttle_bandwidth(self, kbps: u64) -> Box<dyn Transport> {
+        Box::new(TransportWithBandwidth {
+            refill_rate_bytes_per_sec: kbps * 1_000 / 8,
+            transport: Box::new(self),
+        })
+    }
+
+    fn drop_message_bursty(
+        self,
+        p_good_to_bad: f64,
+        p_bad_to_good: f64,
+        loss_good: f64,
+        loss_bad: f64,
+    ) -> Box<dyn Transport> {
+        Box::new(TransportWithBurstyMessageDrop {
+            p_good_to_bad: Bernoulli::new(p_good_to_bad).unwrap(),
+            p_bad_to_good: Bernoulli::new(p_bad_to_good).unwrap(),
+            loss_good: Bernoulli::new(loss_good).unwrap(),
+            loss_bad: Bernoulli::new(loss_bad).unwrap(),
+            transport: Box::new(self),
+        })
+    }
+
+    fn partitioned(self) -> (Box<dyn Transport>, PartitionHandle) {
+        let state = Arc::new(RwLock::new(PartitionState::default()));
+        let handle = PartitionHandle {
+            state: state.clone(),
+        };
+        let transport: Box<dyn Transport> = Box::new(TransportWithPartition {
+            state,
+            transport: Box::new(self),
+        });
+        (transport, handle)
+    }
+
+    fn drop_message_seeded(self, drop_probability: f64, seed: u64) -> Box<dyn Transport> {
+        Box::new(TransportWithMessageDrop {
+            drop_probability: Bernoulli::new(drop_probability).unwrap(),
+            seed: Some(seed),
+            transport: Box::new(self),
+        })
+    }
+
+    fn delay_seeded<D: DelayMillisDist>(self, dist: D, seed: u64) -> Box<dyn Transport> {
+        Box::new(TransportWithDelay {
+            delay_secs: dist,
+            seed: Some(seed),
+            transport: Box::new(self),
+        })
+    }
+
+    fn duplicate<D: DelayMillisDist>(
+        self,
+        dup_probability: f64,
+        extra_delay: D,
+    ) -> Box<dyn Transport> {
+        Box::new(TransportWithDuplicate {
+            dup_probability: Bernoulli::new(dup_probability).unwrap(),
+            extra_delay,
             transport: Box::new(self),
         })
     }
@@ -82,13 +181,14 @@ impl<T: Transport> TransportExt for T {
 
 struct TransportWithMessageDrop {
     drop_probability: Bernoulli,
+    seed: Option<u64>,
     transport: Box<dyn Transport>,
 }
 
 #[async_trait]
 impl Transport for TransportWithMessageDrop {
     async fn open(&self, listen_addr: SocketAddr) -> Result<Box<dyn Socket>, TransportError> {
-        let rng = SmallRng::from_rng(thread_rng()).unwrap();
+        let rng = rng_for(self.seed, listen_addr);
         let socket = self.transport.open(listen_addr).await?;
         Ok(Box::new(SocketWithMessageDrop {
             drop_probability: self.drop_probability,
@@ -122,3 +222,314 @@ impl Socket for SocketWithMessageDrop {
         self.socket.recv().await
     }
 }
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum LinkState {
+    Good,
+    Bad,
+}
+
+struct TransportWithBurstyMessageDrop {
+    p_good_to_bad: Bernoulli,
+    p_bad_to_good: Bernoulli,
+    loss_good: Bernoulli,
+    loss_bad: Bernoulli,
+    transport: Box<dyn Transport>,
+}
+
+#[async_trait]
+impl Transport for TransportWithBurstyMessageDrop {
+    async fn open(&self, listen_addr: SocketAddr) -> Result<Box<dyn Socket>, TransportError> {
+        let rng = SmallRng::from_rng(thread_rng()).unwrap();
+        let socket = self.transport.open(listen_addr).await?;
+        Ok(Box::new(SocketWithBurstyMessageDrop {
+            p_good_to_bad: self.p_good_to_bad,
+            p_bad_to_good: self.p_bad_to_good,
+            loss_good: self.loss_good,
+            loss_bad: self.loss_bad,
+            state: LinkState::Good,
+            socket,
+            rng,
+        }))
+    }
+}
+
+struct SocketWithBurstyMessageDrop {
+    p_good_to_bad: Bernoulli,
+    p_bad_to_good: Bernoulli,
+    loss_good: Bernoulli,
+    loss_bad: Bernoulli,
+    state: LinkState,
+    socket: Box<dyn Socket>,
+    rng: SmallRng,
+}
+
+#[async_trait]
+impl Socket for SocketWithBurstyMessageDrop {
+    async fn send(
+        &mut self,
+        to: SocketAddr,
+        message: ChitchatMessage,
+    ) -> Result<(), TransportError> {
+        self.state = match self.state {
+            LinkState::Good if self.p_good_to_bad.sample(&mut self.rng) => LinkState::Bad,
+            LinkState::Bad if self.p_bad_to_good.sample(&mut self.rng) => LinkState::Good,
+            state => state,
+        };
+        let should_drop = match self.state {
+            LinkState::Good => self.loss_good.sample(&mut self.rng),
+            LinkState::Bad => self.loss_bad.sample(&mut self.rng),
+        };
+        if should_drop {
+            return Ok(());
+        }
+        self.socket.send(to, message).await
+    }
+
+    async fn recv(&mut self) -> Result<(SocketAddr, ChitchatMessage), TransportError> {
+        self.socket.recv().await
+    }
+}
+
+#[derive(Default)]
+struct PartitionState {
+    groups: HashMap<SocketAddr, u64>,
+    next_group_id: u64,
+    cut_pairs: HashSet<(u64, u64)>,
+}
+
+impl PartitionState {
+    fn group_of(&self, addr: SocketAddr) -> u64 {
+        self.groups.get(&addr).copied().unwrap_or(0)
+    }
+
+    fn fresh_group_id(&mut self) -> u64 {
+        self.next_group_id += 1;
+        self.next_group_id
+    }
+}
+
+fn normalized_pair(group_a: u64, group_b: u64) -> (u64, u64) {
+    if group_a <= group_b {
+        (group_a, group_b)
+    } else {
+        (group_b, group_a)
+    }
+}
+
+/// Handle returned by [`TransportExt::partitioned`] that lets a test declare, at
+/// runtime, which addresses can currently reach each other.
+#[derive(Clone)]
+pub struct PartitionHandle {
+    state: Arc<RwLock<PartitionState>>,
+}
+
+impl PartitionHandle {
+    /// Cuts `addr` off from every other address currently known to the partition.
+    pub async fn isolate(&self, addr: SocketAddr) {
+        let mut state = self.state.write().await;
+        let other_groups: HashSet<u64> = state
+            .groups
+            .values()
+            .copied()
+            .chain(std::iter::once(0))
+            .collect();
+        let isolated_group = state.fresh_group_id();
+        state.groups.insert(addr, isolated_group);
+        for other_group in other_groups {
+            if other_group != isolated_group {
+                state
+                    .cut_pairs
+                    .insert(normalized_pair(isolated_group, other_group));
+            }
+        }
+    }
+
+    /// Makes every address in `group_a` unreachable from every address in `group_b`
+    /// (and vice versa), without affecting reachability within each group.
+    pub async fn cut(&self, group_a: &[SocketAddr], group_b: &[SocketAddr]) {
+        let mut state = self.state.write().await;
+        let id_a = state.fresh_group_id();
+        let id_b = state.fresh_group_id();
+        for addr in group_a {
+            state.groups.insert(*addr, id_a);
+        }
+        for addr in group_b {
+            state.groups.insert(*addr, id_b);
+        }
+        state.cut_pairs.insert(normalized_pair(id_a, id_b));
+    }
+
+    /// Heals all partitions, restoring full reachability between every address.
+    pub async fn heal(&self) {
+        let mut state = self.state.write().await;
+        state.groups.clear();
+        state.cut_pairs.clear();
+    }
+}
+
+struct TransportWithPartition {
+    state: Arc<RwLock<PartitionState>>,
+    transport: Box<dyn Transport>,
+}
+
+#[async_trait]
+impl Transport for TransportWithPartition {
+    async fn open(&self, listen_addr: SocketAddr) -> Result<Box<dyn Socket>, TransportError> {
+        let socket = self.transport.open(listen_addr).await?;
+        Ok(Box::new(SocketWithPartition {
+            listen_addr,
+            state: self.state.clone(),
+            socket,
+        }))
+    }
+}
+
+struct SocketWithPartition {
+    listen_addr: SocketAddr,
+    state: Arc<RwLock<PartitionState>>,
+    socket: Box<dyn Socket>,
+}
+
+#[async_trait]
+impl Socket for SocketWithPartition {
+    async fn send(
+        &mut self,
+        to: SocketAddr,
+        message: ChitchatMessage,
+    ) -> Result<(), TransportError> {
+        let is_cut = {
+            let state = self.state.read().await;
+            let local_group = state.group_of(self.listen_addr);
+            let remote_group = state.group_of(to);
+            local_group != remote_group
+                && state
+                    .cut_pairs
+                    .contains(&normalized_pair(local_group, remote_group))
+        };
+        if is_cut {
+            return Ok(());
+        }
+        self.socket.send(to, message).await
+    }
+
+    async fn recv(&mut self) -> Result<(SocketAddr, ChitchatMessage), TransportError> {
+        self.socket.recv().await
+    }
+}
+
+struct TransportWithBandwidth {
+    refill_rate_bytes_per_sec: u64,
+    transport: Box<dyn Transport>,
+}
+
+#[async_trait]
+impl Transport for TransportWithBandwidth {
+    async fn open(&self, listen_addr: SocketAddr) -> Result<Box<dyn Socket>, TransportError> {
+        let socket = self.transport.open(listen_addr).await?;
+        // The bucket starts full, holding up to one second's worth of traffic.
+        let capacity_bytes = self.refill_rate_bytes_per_sec as f64;
+        Ok(Box::new(SocketWithBandwidth {
+            refill_rate_bytes_per_sec: self.refill_rate_bytes_per_sec as f64,
+            capacity_bytes,
+            available_bytes: capacity_bytes,
+            last_refill: Instant::now(),
+            socket,
+        }))
+    }
+}
+
+struct SocketWithBandwidth {
+    refill_rate_bytes_per_sec: f64,
+    capacity_bytes: f64,
+    available_bytes: f64,
+    last_refill: Instant,
+    socket: Box<dyn Socket>,
+}
+
+impl SocketWithBandwidth {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed_secs = (now - self.last_refill).as_secs_f64();
+        self.available_bytes = (self.available_bytes + elapsed_secs * self.refill_rate_bytes_per_sec)
+            .min(self.capacity_bytes);
+        self.last_refill = now;
+    }
+}
+
+#[async_trait]
+impl Socket for SocketWithBandwidth {
+    async fn send(
+        &mut self,
+        to: SocketAddr,
+        message: ChitchatMessage,
+    ) -> Result<(), TransportError> {
+        self.refill();
+        let message_bytes = message.serialized_len() as f64;
+        if message_bytes > self.available_bytes {
+            let deficit_bytes = message_bytes - self.available_bytes;
+            let wait = Duration::from_secs_f64(deficit_bytes / self.refill_rate_bytes_per_sec);
+            tokio::time::sleep(wait).await;
+            self.available_bytes = 0.0;
+            self.last_refill = Instant::now();
+        } else {
+            self.available_bytes -= message_bytes;
+        }
+        self.socket.send(to, message).await
+    }
+
+    async fn recv(&mut self) -> Result<(SocketAddr, ChitchatMessage), TransportError> {
+        self.socket.recv().await
+    }
+}
+
+struct TransportWithDuplicate<D: Distribution<f32> + Send + Sync + 'static> {
+    dup_probability: Bernoulli,
+    extra_delay: D,
+    transport: Box<dyn Transport>,
+}
+
+#[async_trait]
+impl<D: DelayMillisDist> Transport for TransportWithDuplicate<D> {
+    async fn open(&self, listen_addr: SocketAddr) -> Result<Box<dyn Socket>, TransportError> {
+        let rng = SmallRng::from_rng(thread_rng()).unwrap();
+        let socket = self.transport.open(listen_addr).await?;
+        Ok(Box::new(SocketWithDuplicate {
+            dup_probability: self.dup_probability,
+            extra_delay: self.extra_delay.clone(),
+            socket: Arc::new(RwLock::new(socket)),
+            rng,
+        }))
+    }
+}
+
+struct SocketWithDuplicate<D: Distribution<f32> + Send + Sync + 'static> {
+    dup_probability: Bernoulli,
+    extra_delay: D,
+    socket: Arc<RwLock<Box<dyn Socket>>>,
+    rng: SmallRng,
+}
+
+#[async_trait]
+impl<D: DelayMillisDist> Socket for SocketWithDuplicate<D> {
+    async fn send(
+        &mut self,
+        to: SocketAddr,
+        message: ChitchatMessage,
+    ) -> Result<(), TransportError> {
+        if self.dup_probability.sample(&mut self.rng) {
+            let socket_clone = self.socket.clone();
+            let extra_delay_secs = self.extra_delay.sample(&mut self.rng);
+            let duplicate = message.clone();
+            tokio::task::spawn(async move {
+                tokio::time::sleep(Duration::from_secs_f32(extra_delay_secs)).await;
+                let _ = socket_clone.write().await.send(to, duplicate).await;
+            });
+        }
+        self.socket.write().await.send(to, message).await
+    }
+
+    async fn recv(&mut self) -> Result<(SocketAddr, ChitchatMessage), TransportError> {
+        self.socket.write().await.recv().await
+    }
+}